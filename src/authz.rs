@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+use axum_core::extract::FromRequestParts;
+use http::request::Parts;
+
+use crate::Error;
+
+/// Group memberships for the authenticated identity, as returned by
+/// [`crate::NegotiateBackend::groups`] for the completed security context.
+/// Lets handlers do role checks without a second round trip to a directory
+/// service. The default `groups` implementation, which
+/// [`crate::KerberosSpnegoBackend`] uses, reads no authorization data (no
+/// PAC) and always returns an empty set. Never absent - the middleware always inserts this
+/// alongside the identity, on every authenticated path - but empty
+/// whenever there is no PAC to read: the mechanism provided no
+/// authorization data, or the request was authenticated via a session
+/// ticket or the Basic-auth fallback, neither of which carries a live
+/// security context to read one from.
+#[derive(Clone, Debug, Default)]
+pub struct Groups(pub Vec<Box<str>>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Groups {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Self>()
+            .cloned()
+            .ok_or(Error::GroupsExtensionNotFound)
+    }
+}
@@ -0,0 +1,181 @@
+use libgssapi::{
+    context::{ClientCtx, SecurityContext, ServerCtx},
+    credential::{Cred, CredUsage},
+    name::Name,
+    oid::{OidSet, GSS_MECH_SPNEGO, GSS_NT_KRB5_PRINCIPAL},
+};
+
+use crate::{Error, Upn};
+
+/// Produces the server-side GSSAPI credential/context and maps a completed
+/// negotiation into an application-defined identity. Implement this to
+/// plug in a different credential source (e.g. a keytab-specific store or
+/// delegated credentials) or a richer identity type than [`Upn`] - for
+/// example one that also carries the realm or the negotiated mechanism.
+pub trait NegotiateBackend: Clone + Send + Sync + 'static {
+    /// The type inserted into request extensions once negotiation
+    /// completes.
+    type Identity: Clone + Send + Sync + 'static;
+
+    /// Acquires the server credential and builds a fresh [`ServerCtx`] to
+    /// drive one negotiation. `channel_binding`, when present, is the RFC
+    /// 5929 `tls-server-end-point` value for the TLS connection the
+    /// negotiation is happening over (see
+    /// [`crate::NegotiateAuthLayer::with_channel_binding`]); backends built
+    /// on `libgssapi` should pass it through to the context so a token
+    /// relayed from a different endpoint is rejected instead of accepted.
+    fn new_server_ctx(&self, channel_binding: Option<&[u8]>) -> Result<ServerCtx, Error>;
+
+    /// Maps a completed security context into this backend's identity type.
+    /// Implementations can inspect the source name, the negotiated
+    /// mechanism, or authorization data to enforce realm allow-lists or
+    /// build a richer identity than a bare UPN; returning `Err` rejects the
+    /// request instead of calling the inner service.
+    fn identity(&self, ctx: &ServerCtx) -> Result<Self::Identity, Error>;
+
+    /// Extracts group memberships from the completed context's
+    /// authorization data (e.g. a Kerberos PAC), surfaced to handlers via
+    /// the [`crate::Groups`] extractor. The default implementation always
+    /// returns an empty set: reading a PAC's group SIDs means parsing and
+    /// signature-verifying the MS-PAC structure, which `libgssapi`'s safe
+    /// surface has no support for and this crate isn't going to hand-roll
+    /// against untrusted input. Backends that need real group data should
+    /// override this - typically by looking the identity up against a
+    /// directory service instead of reading ticket authorization data -
+    /// rather than expecting the default to provide it.
+    fn groups(&self, _ctx: &ServerCtx) -> Result<Vec<Box<str>>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+/// Backends that can authenticate a plaintext username/password pair,
+/// needed for the Basic-auth fallback enabled by
+/// [`crate::NegotiateAuthLayer::with_basic_auth_fallback`].
+pub trait PasswordBackend: NegotiateBackend {
+    /// Validates `username`/`password` and, on success, returns the same
+    /// kind of identity a completed Negotiate exchange would produce. A
+    /// wrong password is a normal `Err`, not a panic: callers map it to a
+    /// fresh 401 challenge.
+    fn verify_password(&self, username: &str, password: &str) -> Result<Self::Identity, Error>;
+}
+
+/// Backends whose identity can round-trip through the subject string of a
+/// session ticket. Required to use [`crate::NegotiateAuthLayer::with_session`]
+/// or [`crate::NegotiateAuthLayer::with_sliding_session`].
+pub trait SessionSubject: Sized {
+    fn to_subject(&self) -> &str;
+    fn from_subject(subject: Box<str>) -> Self;
+}
+
+impl SessionSubject for Upn {
+    fn to_subject(&self) -> &str {
+        &self.0
+    }
+
+    fn from_subject(subject: Box<str>) -> Self {
+        Upn(subject)
+    }
+}
+
+/// The default [`NegotiateBackend`]: acquires a Kerberos/SPNEGO accept
+/// credential for a fixed SPN and maps the completed context's source name
+/// straight into a [`Upn`]. This is the behavior `axum-negotiate` shipped
+/// before backends were pluggable. It does not override `groups`, so
+/// [`crate::Groups`] is always empty for it - see that method's docs for
+/// why, and implement a custom [`NegotiateBackend`] if you need real group
+/// data.
+#[derive(Clone)]
+pub struct KerberosSpnegoBackend {
+    spn: String,
+}
+
+impl KerberosSpnegoBackend {
+    pub fn new(spn: String) -> Result<Self, Error> {
+        //TODO: check if libgssapi really can't handle utf16 characters. remove the ascii check if
+        //it does.
+        if spn.is_ascii() {
+            Ok(Self { spn })
+        } else {
+            Err(Error::InvalidSpn)
+        }
+    }
+}
+
+impl NegotiateBackend for KerberosSpnegoBackend {
+    type Identity = Upn;
+
+    fn new_server_ctx(&self, channel_binding: Option<&[u8]>) -> Result<ServerCtx, Error> {
+        let name = Name::new(self.spn.as_bytes(), Some(&GSS_NT_KRB5_PRINCIPAL))?
+            .canonicalize(Some(&GSS_MECH_SPNEGO))?;
+        let cred = {
+            let mut s = OidSet::new()?;
+            s.add(&GSS_MECH_SPNEGO)?;
+            Cred::acquire(Some(&name), None, CredUsage::Accept, Some(&s))?
+        };
+
+        match channel_binding {
+            // `libgssapi` threads the RFC 5929 channel binding through to
+            // gss_accept_sec_context's input_chan_bindings; a client token
+            // produced against a different TLS endpoint fails the first
+            // `step` with a bad-bindings `GssApi` error instead of
+            // completing.
+            Some(binding) => Ok(ServerCtx::with_channel_bindings(cred, binding)),
+            None => Ok(ServerCtx::new(cred)),
+        }
+    }
+
+    fn identity(&self, ctx: &ServerCtx) -> Result<Self::Identity, Error> {
+        Ok(Upn(ctx.source_name()?.to_string().into()))
+    }
+}
+
+impl PasswordBackend for KerberosSpnegoBackend {
+    fn verify_password(&self, username: &str, password: &str) -> Result<Self::Identity, Error> {
+        let client_name = Name::new(username.as_bytes(), Some(&GSS_NT_KRB5_PRINCIPAL))?;
+        let mut mechs = OidSet::new()?;
+        mechs.add(&GSS_MECH_SPNEGO)?;
+
+        // `libgssapi` surfaces krb5's password-based credential acquisition
+        // as an extension of `Cred::acquire`; if the mechanism library in
+        // use lacks gss_acquire_cred_with_password this comes back as a
+        // `GssApi` error rather than panicking.
+        let client_cred = Cred::acquire_with_password(
+            Some(&client_name),
+            password.as_bytes(),
+            None,
+            CredUsage::Initiate,
+            Some(&mechs),
+        )?;
+
+        let server_name = Name::new(self.spn.as_bytes(), Some(&GSS_NT_KRB5_PRINCIPAL))?
+            .canonicalize(Some(&GSS_MECH_SPNEGO))?;
+        let mut client_ctx = ClientCtx::new(client_cred, server_name, Some(&GSS_MECH_SPNEGO), None);
+        // No real TLS connection backs this loopback handshake, so there is
+        // nothing to bind to.
+        let mut server_ctx = self.new_server_ctx(None)?;
+
+        // Loopback init/accept handshake: drive both sides of the exchange
+        // ourselves so a correct password is all that's needed, without the
+        // client ever seeing a real request.
+        let mut client_token = client_ctx.step(None)?;
+        while !server_ctx.is_complete() {
+            let Some(token) = client_token.take() else {
+                return Err(Error::BasicAuthFailed {
+                    basic_auth_realm: None,
+                });
+            };
+            let server_token = server_ctx.step(&token)?;
+            if server_ctx.is_complete() {
+                break;
+            }
+            let Some(server_token) = server_token else {
+                return Err(Error::BasicAuthFailed {
+                    basic_auth_realm: None,
+                });
+            };
+            client_token = client_ctx.step(Some(&server_token))?;
+        }
+
+        self.identity(&server_ctx)
+    }
+}
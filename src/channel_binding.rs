@@ -0,0 +1,157 @@
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// How strictly [`crate::NegotiateAuthLayer::with_channel_binding`] enforces
+/// the `tls-server-end-point` channel binding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelBindingMode {
+    /// Accept clients that don't send a channel binding at all - most
+    /// don't yet. The default: when the first `ServerCtx::step` of a fresh
+    /// negotiation fails against the configured binding, for any reason,
+    /// the middleware retries once against a fresh context with no binding
+    /// at all, so a client that simply isn't binding-aware still
+    /// authenticates. That retry checks no binding, so a token bound to a
+    /// different TLS endpoint is accepted on it as well; only `Strict`
+    /// rejects a mismatched binding.
+    #[default]
+    Relaxed,
+    /// Like `Relaxed`'s binding check, but skips the no-binding retry: a
+    /// client that doesn't send a matching `tls-server-end-point` binding
+    /// is rejected outright, including one that doesn't send a binding at
+    /// all. Breaks clients that aren't channel-binding aware.
+    Strict,
+}
+
+/// Configuration for the optional TLS channel binding, see
+/// [`crate::NegotiateAuthLayer::with_channel_binding`].
+pub(crate) struct ChannelBindingConfig {
+    pub(crate) binding: Vec<u8>,
+    pub(crate) mode: ChannelBindingMode,
+}
+
+/// Computes the RFC 5929 `tls-server-end-point` channel binding for a DER
+/// encoded server certificate: the hash of the whole certificate using its
+/// own signing hash algorithm, with MD5 and SHA-1 upgraded to SHA-256 as
+/// the RFC requires.
+///
+/// Reads the certificate's `signatureAlgorithm` field out of the DER by
+/// walking its ASN.1 structure (not by scanning the whole blob for OID
+/// bytes, which can match unrelated fields - the SPKI algorithm, an
+/// extension OID, trailing chain data - and silently produce the wrong
+/// hash). Callers whose certificate uses an algorithm this doesn't
+/// recognize, or who would rather not depend on this parse at all, can
+/// compute the hash themselves and pass it to
+/// [`crate::NegotiateAuthLayer::with_channel_binding_hash`] instead.
+pub(crate) fn tls_server_end_point_hash(cert_der: &[u8]) -> Vec<u8> {
+    match signature_hash_algorithm(cert_der) {
+        Some(SignatureHashAlgorithm::Sha256) => Sha256::digest(cert_der).to_vec(),
+        Some(SignatureHashAlgorithm::Sha384) => Sha384::digest(cert_der).to_vec(),
+        Some(SignatureHashAlgorithm::Sha512) => Sha512::digest(cert_der).to_vec(),
+        // RFC 5929: MD5 and SHA-1 are upgraded to SHA-256. An algorithm we
+        // don't recognize (or a certificate we failed to parse) also falls
+        // back here, since SHA-256 is the RFC's own default for this case.
+        Some(SignatureHashAlgorithm::WeakOrUnknown) | None => Sha256::digest(cert_der).to_vec(),
+    }
+}
+
+enum SignatureHashAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+    WeakOrUnknown,
+}
+
+/// DER encodings of the handful of `signatureAlgorithm` OIDs we bother
+/// telling apart, as the raw content octets of the OBJECT IDENTIFIER (no
+/// tag or length octets - `signature_algorithm_oid` already strips those).
+const SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const ECDSA_WITH_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x04];
+
+fn signature_hash_algorithm(cert_der: &[u8]) -> Option<SignatureHashAlgorithm> {
+    let oid = signature_algorithm_oid(cert_der)?;
+
+    Some(if oid == SHA256_WITH_RSA || oid == ECDSA_WITH_SHA256 {
+        SignatureHashAlgorithm::Sha256
+    } else if oid == SHA384_WITH_RSA || oid == ECDSA_WITH_SHA384 {
+        SignatureHashAlgorithm::Sha384
+    } else if oid == SHA512_WITH_RSA || oid == ECDSA_WITH_SHA512 {
+        SignatureHashAlgorithm::Sha512
+    } else {
+        SignatureHashAlgorithm::WeakOrUnknown
+    })
+}
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_OBJECT_IDENTIFIER: u8 = 0x06;
+
+/// Reads the OID bytes out of `Certificate.signatureAlgorithm` -
+/// `SEQUENCE { tbsCertificate ANY, signatureAlgorithm SEQUENCE { algorithm
+/// OBJECT IDENTIFIER, ... }, signatureValue BIT STRING }` - by walking just
+/// enough of the DER structure to skip over `tbsCertificate` and read the
+/// OID that follows it, without parsing `tbsCertificate` itself.
+fn signature_algorithm_oid(cert_der: &[u8]) -> Option<&[u8]> {
+    let mut top = DerReader::new(cert_der);
+    let (certificate_tag, certificate_body) = top.read_tlv()?;
+    if certificate_tag != TAG_SEQUENCE {
+        return None;
+    }
+
+    let mut certificate_body = DerReader::new(certificate_body);
+    let (tbs_tag, _tbs_certificate) = certificate_body.read_tlv()?;
+    if tbs_tag != TAG_SEQUENCE {
+        return None;
+    }
+
+    let (algorithm_tag, algorithm_identifier) = certificate_body.read_tlv()?;
+    if algorithm_tag != TAG_SEQUENCE {
+        return None;
+    }
+
+    let (oid_tag, oid) = DerReader::new(algorithm_identifier).read_tlv()?;
+    (oid_tag == TAG_OBJECT_IDENTIFIER).then_some(oid)
+}
+
+/// Reads a flat sequence of DER tag-length-value triples, just enough to
+/// skip or extract top-level elements - not a general ASN.1 parser.
+struct DerReader<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { remaining: bytes }
+    }
+
+    /// Reads one tag-length-value triple and advances past it, returning
+    /// the tag byte and the value bytes. Only supports tags that fit in a
+    /// single identifier octet (true of every tag this module looks at)
+    /// and definite-form lengths up to `u32::MAX`.
+    fn read_tlv(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.remaining.split_first()?;
+        let (&first_length_byte, rest) = rest.split_first()?;
+
+        let (length, rest) = if first_length_byte & 0x80 == 0 {
+            (first_length_byte as usize, rest)
+        } else {
+            let octets = (first_length_byte & 0x7f) as usize;
+            if octets == 0 || octets > 4 {
+                // Indefinite-form length, or implausibly large for a
+                // certificate: not something we need to support here.
+                return None;
+            }
+            let (length_bytes, rest) = rest.split_at_checked(octets)?;
+            let mut length = 0usize;
+            for byte in length_bytes {
+                length = (length << 8) | (*byte as usize);
+            }
+            (length, rest)
+        };
+
+        let (value, rest) = rest.split_at_checked(length)?;
+        self.remaining = rest;
+        Some((tag, value))
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use libgssapi::context::ServerCtx;
+use rand::RngCore;
+
+/// Upper bound on the number of half-open negotiations kept in memory at
+/// once, so a flood of requests that never complete their handshake can't
+/// grow the store without bound.
+const MAX_PENDING: usize = 1024;
+
+/// Opaque handle correlating the legs of a multi-pass SPNEGO/NTLM
+/// negotiation that span more than one request on the same keep-alive
+/// connection. Minted by [`ContinuationStore::insert`] and sent to the
+/// client in the `x-negotiate-continuation` response header, next to the
+/// `WWW-Authenticate` challenge, so it can be presented again on the next
+/// leg.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ContinuationId(u128);
+
+impl ContinuationId {
+    fn new() -> Self {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(u128::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        hex::encode(self.0.to_le_bytes())
+    }
+
+    pub(crate) fn decode(s: &str) -> Option<Self> {
+        let mut bytes = [0u8; 16];
+        hex::decode_to_slice(s, &mut bytes).ok()?;
+        Some(Self(u128::from_le_bytes(bytes)))
+    }
+}
+
+/// Holds partially-completed [`ServerCtx`]s between the legs of a multi-pass
+/// negotiation, keyed by [`ContinuationId`]. Entries older than the
+/// configured TTL are swept out lazily whenever the store is touched, so a
+/// client that abandons the handshake halfway through doesn't leak memory.
+pub(crate) struct ContinuationStore {
+    entries: DashMap<ContinuationId, (ServerCtx, Instant)>,
+    // Tracks `entries.len()` ourselves rather than calling `DashMap::len`,
+    // so the `MAX_PENDING` bound can be reserved atomically: `len` followed
+    // by a separate `insert` is a check-then-act race that lets concurrent
+    // callers all observe room and all insert, overshooting the cap.
+    count: AtomicUsize,
+    ttl: Duration,
+}
+
+impl ContinuationStore {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            count: AtomicUsize::new(0),
+            ttl,
+        }
+    }
+
+    /// Stores `ctx` and returns the id it can be resumed with, or `None` if
+    /// the store is full of other half-open negotiations.
+    pub(crate) fn insert(&self, ctx: ServerCtx) -> Option<ContinuationId> {
+        self.sweep();
+        if !self.try_reserve() {
+            return None;
+        }
+        let id = ContinuationId::new();
+        self.entries.insert(id, (ctx, Instant::now()));
+        Some(id)
+    }
+
+    /// Removes and returns the context for `id`, if it is still present and
+    /// has not expired. An id can only ever be taken once: this makes reuse
+    /// of a completed or already-resumed id impossible.
+    pub(crate) fn take(&self, id: ContinuationId) -> Option<ServerCtx> {
+        self.sweep();
+        let ctx = self.entries.remove(&id).map(|(_, (ctx, _))| ctx);
+        if ctx.is_some() {
+            self.count.fetch_sub(1, Ordering::AcqRel);
+        }
+        ctx
+    }
+
+    /// Atomically claims one of the `MAX_PENDING` slots, or reports the
+    /// store as full without touching `entries`.
+    fn try_reserve(&self) -> bool {
+        loop {
+            let current = self.count.load(Ordering::Acquire);
+            if current >= MAX_PENDING {
+                return false;
+            }
+            if self
+                .count
+                .compare_exchange_weak(current, current + 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return true;
+            }
+        }
+    }
+
+    fn sweep(&self) {
+        let ttl = self.ttl;
+        let count = &self.count;
+        self.entries.retain(|_, (_, inserted)| {
+            let alive = inserted.elapsed() < ttl;
+            if !alive {
+                count.fetch_sub(1, Ordering::AcqRel);
+            }
+            alive
+        });
+    }
+}
@@ -1,15 +1,16 @@
 #![deny(unsafe_code)]
 #![deny(clippy::unwrap_used)]
 
-use std::{borrow::Borrow, ops::Deref};
+mod authz;
+mod backend;
+mod channel_binding;
+mod continuation;
+mod session;
+
+use std::{borrow::Borrow, ops::Deref, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use libgssapi::{
-    context::{SecurityContext, ServerCtx},
-    credential::{Cred, CredUsage},
-    name::Name,
-    oid::{OidSet, GSS_MECH_SPNEGO, GSS_NT_KRB5_PRINCIPAL},
-};
+use libgssapi::context::SecurityContext;
 use log::{debug, error};
 
 use base64::{engine::general_purpose::STANDARD, Engine as _};
@@ -18,16 +19,32 @@ use axum_core::{
     extract::FromRequestParts,
     response::{IntoResponse, Response},
 };
+pub use authz::Groups;
+pub use backend::{KerberosSpnegoBackend, NegotiateBackend, PasswordBackend, SessionSubject};
+pub use channel_binding::ChannelBindingMode;
+use channel_binding::{tls_server_end_point_hash, ChannelBindingConfig};
+use continuation::{ContinuationId, ContinuationStore};
 use futures_util::future::BoxFuture;
 use http::{
-    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    header::{AUTHORIZATION, COOKIE, SET_COOKIE, WWW_AUTHENTICATE},
     request::Parts,
-    HeaderValue, Request, StatusCode,
+    HeaderName, HeaderValue, Request, StatusCode,
 };
+use session::{extract_cookie, TicketSigner, SESSION_COOKIE};
 use thiserror::Error;
 use tower_layer::Layer;
 use tower_service::Service;
 
+/// Header carrying the opaque [`continuation::ContinuationId`] that
+/// correlates the legs of a multi-pass negotiation. Clients must echo back
+/// whatever value they were given here on the next request of the same
+/// handshake.
+const CONTINUATION_HEADER: &str = "x-negotiate-continuation";
+
+/// How long a half-open negotiation is kept around waiting for its next leg
+/// before it is discarded, by default.
+const DEFAULT_CONTINUATION_TTL: Duration = Duration::from_secs(10);
+
 pub trait NextMiddlewareError: std::error::Error + IntoResponse + Send + Sync {
     fn box_into_response(self: Box<Self>) -> Response;
 }
@@ -49,27 +66,48 @@ pub enum Error {
     #[error("libgssapi: {0}")]
     GssApi(#[from] libgssapi::error::Error),
 
-    #[error("multistage spnego is requested but currently not supported")]
-    MultipassSpnego,
+    #[error("too many half-open negotiations in flight, try again later")]
+    NegotiationStoreFull,
 
+    /// `basic_auth_realm` is the realm to offer in the `WWW-Authenticate:
+    /// Basic` challenge alongside `Negotiate`, when the Basic-auth fallback
+    /// is enabled; `None` if it isn't, in which case only `Negotiate` is
+    /// offered.
+    #[error("basic authentication failed")]
+    BasicAuthFailed { basic_auth_realm: Option<String> },
+
+    /// Covers a missing `Authorization` header, an unrecognized scheme, and
+    /// a `Basic` header while the fallback is disabled; a malformed `Basic`
+    /// payload is reported as `BasicAuthFailed`. See `BasicAuthFailed` for
+    /// `basic_auth_realm`.
     #[error("invalid authorization header")]
-    InvalidAuthorizationHeader,
+    InvalidAuthorizationHeader { basic_auth_realm: Option<String> },
 
     #[error("invalid gssapi_data")]
     InvalidGssapiData,
 
     #[error("UPN extension not found in request")]
     UpnExtensionNotFound,
+
+    #[error("Groups extension not found in request")]
+    GroupsExtensionNotFound,
 }
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         match self {
-            Self::InvalidSpn | Self::MultipassSpnego | Self::GssApi(_) => {
+            Self::InvalidSpn | Self::GssApi(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "internal server error").into_response()
             }
+            Self::NegotiationStoreFull => {
+                (StatusCode::SERVICE_UNAVAILABLE, "too many pending negotiations").into_response()
+            }
             Self::NextMiddleware(error) => error.box_into_response(),
             Self::InvalidGssapiData => (StatusCode::BAD_REQUEST, "bad request").into_response(),
-            Self::UpnExtensionNotFound | Self::InvalidAuthorizationHeader => {
+            Self::InvalidAuthorizationHeader { basic_auth_realm }
+            | Self::BasicAuthFailed { basic_auth_realm } => {
+                unauthorized_challenge(basic_auth_realm.as_deref())
+            }
+            Self::UpnExtensionNotFound | Self::GroupsExtensionNotFound => {
                 let mut response = (StatusCode::UNAUTHORIZED, "unauthorized").into_response();
                 response
                     .headers_mut()
@@ -80,30 +118,149 @@ impl IntoResponse for Error {
     }
 }
 
+/// Configuration for the optional session-ticket mode, see
+/// [`NegotiateAuthLayer::with_session`]. Generic over the backend so the
+/// ticket subject can be converted to and from `Be::Identity` without
+/// requiring every backend to support sessions.
+struct SessionConfig<Be: NegotiateBackend> {
+    signer: TicketSigner,
+    ttl: Duration,
+    sliding_expiry: bool,
+    to_subject: fn(&Be::Identity) -> &str,
+    from_subject: fn(Box<str>) -> Be::Identity,
+}
+
+/// Configuration for the optional Basic-auth fallback, see
+/// [`NegotiateAuthLayer::with_basic_auth_fallback`].
+struct BasicAuthConfig<Be: NegotiateBackend> {
+    realm: String,
+    verify_password: fn(&Be, &str, &str) -> Result<Be::Identity, Error>,
+}
+
 #[derive(Clone)]
-pub struct NegotiateAuthLayer {
-    spn: String,
+pub struct NegotiateAuthLayer<Be = KerberosSpnegoBackend> {
+    backend: Be,
+    continuations: Arc<ContinuationStore>,
+    session: Option<Arc<SessionConfig<Be>>>,
+    basic_auth: Option<Arc<BasicAuthConfig<Be>>>,
+    channel_binding: Option<Arc<ChannelBindingConfig>>,
 }
 
-impl NegotiateAuthLayer {
+impl NegotiateAuthLayer<KerberosSpnegoBackend> {
     pub fn new(spn: String) -> Result<Self, Error> {
-        //TODO: check if libgssapi really can't handle utf16 characters. remove the ascii check if
-        //it does.
-        if spn.is_ascii() {
-            Ok(Self { spn })
-        } else {
-            Err(Error::InvalidSpn)
+        Ok(Self::with_backend(KerberosSpnegoBackend::new(spn)?))
+    }
+}
+
+impl<Be: NegotiateBackend> NegotiateAuthLayer<Be> {
+    /// Builds a layer around a custom [`NegotiateBackend`] instead of the
+    /// default Kerberos/SPNEGO one.
+    pub fn with_backend(backend: Be) -> Self {
+        Self {
+            backend,
+            continuations: Arc::new(ContinuationStore::new(DEFAULT_CONTINUATION_TTL)),
+            session: None,
+            basic_auth: None,
+            channel_binding: None,
         }
     }
+
+    /// Overrides how long a half-open multi-pass negotiation is kept around
+    /// waiting for its next leg before being discarded. Defaults to 10
+    /// seconds, which comfortably covers a client's next request on the same
+    /// keep-alive connection without holding abandoned handshakes forever.
+    pub fn with_continuation_ttl(mut self, ttl: Duration) -> Self {
+        self.continuations = Arc::new(ContinuationStore::new(ttl));
+        self
+    }
+
+    /// Binds negotiation to the TLS connection it happens over (RFC 5929
+    /// `tls-server-end-point`), so a Negotiate token captured on one
+    /// connection can't be relayed to authenticate a different one.
+    /// `server_cert_der` is the listener's own certificate, DER encoded;
+    /// the channel binding is derived from it by hashing with its
+    /// signature algorithm (MD5/SHA-1 are upgraded to SHA-256, per the
+    /// RFC). If your TLS stack can't hand you the raw certificate, or you
+    /// want to avoid this crate's best-effort algorithm detection, compute
+    /// the binding yourself and use [`Self::with_channel_binding_hash`]
+    /// instead.
+    ///
+    /// See [`ChannelBindingMode`] for what `mode` controls: `Relaxed` (the
+    /// default elsewhere in this API) accepts clients that don't send a
+    /// channel binding; `Strict` rejects them.
+    pub fn with_channel_binding(self, server_cert_der: Vec<u8>, mode: ChannelBindingMode) -> Self {
+        self.with_channel_binding_hash(tls_server_end_point_hash(&server_cert_der), mode)
+    }
+
+    /// Like [`Self::with_channel_binding`], but takes an already-computed
+    /// `tls-server-end-point` hash instead of a raw certificate.
+    pub fn with_channel_binding_hash(mut self, binding: Vec<u8>, mode: ChannelBindingMode) -> Self {
+        self.channel_binding = Some(Arc::new(ChannelBindingConfig { binding, mode }));
+        self
+    }
 }
 
-impl<I> Layer<I> for NegotiateAuthLayer {
-    type Service = NegotiateAuthLayerMiddleware<I>;
+impl<Be: NegotiateBackend> NegotiateAuthLayer<Be>
+where
+    Be::Identity: SessionSubject,
+{
+    /// Enables session tickets: after a successful GSSAPI negotiation, a
+    /// signed cookie encoding the identity and an expiry is issued, and
+    /// requests presenting a still-valid ticket skip GSSAPI entirely.
+    /// `secret` keys the HMAC used to sign tickets and must stay stable
+    /// across restarts for existing tickets to keep validating; `ttl`
+    /// bounds how long a ticket is accepted for.
+    pub fn with_session(mut self, secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        self.session = Some(Arc::new(SessionConfig {
+            signer: TicketSigner::new(secret.into()),
+            ttl,
+            sliding_expiry: false,
+            to_subject: Be::Identity::to_subject,
+            from_subject: Be::Identity::from_subject,
+        }));
+        self
+    }
+
+    /// Like [`Self::with_session`], but a fresh ticket is issued on every
+    /// request that presents a still-valid one, extending the session for
+    /// as long as the client keeps using it.
+    pub fn with_sliding_session(mut self, secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        self.session = Some(Arc::new(SessionConfig {
+            signer: TicketSigner::new(secret.into()),
+            ttl,
+            sliding_expiry: true,
+            to_subject: Be::Identity::to_subject,
+            from_subject: Be::Identity::from_subject,
+        }));
+        self
+    }
+}
+
+impl<Be: PasswordBackend> NegotiateAuthLayer<Be> {
+    /// Also accepts `Authorization: Basic <base64 user:pass>` on the
+    /// fallback path: the 401 challenge offers both `Negotiate` and
+    /// `Basic realm="<realm>"`, preferring Negotiate when a client can do
+    /// both. Useful for browsers and CLIs that can't perform SPNEGO.
+    pub fn with_basic_auth_fallback(mut self, realm: String) -> Self {
+        self.basic_auth = Some(Arc::new(BasicAuthConfig {
+            realm,
+            verify_password: Be::verify_password,
+        }));
+        self
+    }
+}
+
+impl<I, Be: NegotiateBackend> Layer<I> for NegotiateAuthLayer<Be> {
+    type Service = NegotiateAuthLayerMiddleware<I, Be>;
 
     fn layer(&self, inner: I) -> Self::Service {
         Self::Service {
             inner,
-            spn: self.spn.to_owned(),
+            backend: self.backend.clone(),
+            continuations: self.continuations.clone(),
+            session: self.session.clone(),
+            basic_auth: self.basic_auth.clone(),
+            channel_binding: self.channel_binding.clone(),
         }
     }
 }
@@ -144,17 +301,22 @@ impl Deref for Upn {
 }
 
 #[derive(Clone)]
-pub struct NegotiateAuthLayerMiddleware<I> {
+pub struct NegotiateAuthLayerMiddleware<I, Be = KerberosSpnegoBackend> {
     inner: I,
-    spn: String,
+    backend: Be,
+    continuations: Arc<ContinuationStore>,
+    session: Option<Arc<SessionConfig<Be>>>,
+    basic_auth: Option<Arc<BasicAuthConfig<Be>>>,
+    channel_binding: Option<Arc<ChannelBindingConfig>>,
 }
 
-impl<I, B> Service<Request<B>> for NegotiateAuthLayerMiddleware<I>
+impl<I, ReqBody, Be> Service<Request<ReqBody>> for NegotiateAuthLayerMiddleware<I, Be>
 where
-    I: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    I: Service<Request<ReqBody>, Response = Response> + Clone + Send + 'static,
     I::Error: NextMiddlewareError,
     I::Future: Send + 'static,
-    B: Send + 'static,
+    ReqBody: Send + 'static,
+    Be: NegotiateBackend,
 {
     type Response = I::Response;
 
@@ -171,25 +333,100 @@ where
             .map_err(|e| Error::NextMiddleware(Box::new(e)))
     }
 
-    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
         let inner = self.inner.clone();
         let mut inner = std::mem::replace(&mut self.inner, inner);
 
-        let spn = self.spn.clone();
+        let backend = self.backend.clone();
+        let continuations = self.continuations.clone();
+        let session = self.session.clone();
+        let basic_auth = self.basic_auth.clone();
+        let channel_binding = self.channel_binding.clone();
 
         Box::pin(async move {
+            if let Some(session) = &session {
+                let ticket = req
+                    .headers()
+                    .get(COOKIE)
+                    .and_then(|x| x.to_str().ok())
+                    .and_then(|x| extract_cookie(x, SESSION_COOKIE));
+
+                if let Some(identity) = ticket
+                    .and_then(|t| session.signer.verify(&t))
+                    .map(|subject| (session.from_subject)(subject.into_boxed_str()))
+                {
+                    req.extensions_mut().insert(identity.clone());
+                    // A session ticket only carries the subject, not group
+                    // memberships, so there is no PAC to re-derive them
+                    // from here; handlers relying on `Groups` only see
+                    // anything once the client re-negotiates.
+                    req.extensions_mut().insert(Groups::default());
+
+                    let mut response = inner
+                        .call(req)
+                        .await
+                        .map_err(|x| Error::NextMiddleware(Box::new(x)))?;
+
+                    if session.sliding_expiry {
+                        set_session_cookie(&mut response, session, &identity);
+                    }
+
+                    return Ok(response);
+                }
+            }
+
             let Some(authorization_header) = req
                 .headers()
                 .get(AUTHORIZATION)
                 .and_then(|x| x.to_str().ok())
             else {
                 debug!("authorization header not present");
-                return Err(Error::InvalidAuthorizationHeader);
+                return Err(Error::InvalidAuthorizationHeader {
+                    basic_auth_realm: basic_auth.as_deref().map(|b| b.realm.clone()),
+                });
             };
 
+            if let Some(encoded) = authorization_header.strip_prefix("Basic ") {
+                let Some(basic_auth) = &basic_auth else {
+                    debug!("authorization header has scheme \"Basic\" but the fallback is disabled");
+                    return Err(Error::InvalidAuthorizationHeader {
+                        basic_auth_realm: None,
+                    });
+                };
+
+                let identity = STANDARD
+                    .decode(encoded)
+                    .ok()
+                    .and_then(|raw| String::from_utf8(raw).ok())
+                    .and_then(|raw| raw.split_once(':').map(|(u, p)| (u.to_owned(), p.to_owned())))
+                    .and_then(|(user, pass)| (basic_auth.verify_password)(&backend, &user, &pass).ok());
+
+                let Some(identity) = identity else {
+                    debug!("basic authentication failed");
+                    return Err(Error::BasicAuthFailed {
+                        basic_auth_realm: Some(basic_auth.realm.clone()),
+                    });
+                };
+
+                req.extensions_mut().insert(identity);
+                // Basic-auth verification doesn't go through a GSSAPI
+                // security context, so there is no PAC to read groups from
+                // either.
+                req.extensions_mut().insert(Groups::default());
+
+                let response = inner
+                    .call(req)
+                    .await
+                    .map_err(|x| Error::NextMiddleware(Box::new(x)))?;
+
+                return Ok(response);
+            }
+
             let Some(gssapi_data) = authorization_header.strip_prefix("Negotiate ") else {
-                debug!("authorization header has no prefix \"Negotiate\"");
-                return Err(Error::InvalidAuthorizationHeader);
+                debug!("authorization header has no recognized scheme");
+                return Err(Error::InvalidAuthorizationHeader {
+                    basic_auth_realm: basic_auth.as_deref().map(|b| b.realm.clone()),
+                });
             };
 
             let Ok(gssapi_data) = STANDARD.decode(gssapi_data) else {
@@ -197,17 +434,72 @@ where
                 return Err(Error::InvalidGssapiData);
             };
 
-            let mut ctx = new_server_ctx(&spn)?;
+            let continuation_id = req
+                .headers()
+                .get(CONTINUATION_HEADER)
+                .and_then(|x| x.to_str().ok())
+                .and_then(ContinuationId::decode);
+
+            let resumed = continuation_id.and_then(|id| continuations.take(id));
+            let is_fresh_negotiation = resumed.is_none();
+
+            let mut ctx = match resumed {
+                Some(ctx) => ctx,
+                None => backend.new_server_ctx(channel_binding.as_deref().map(|c| c.binding.as_slice()))?,
+            };
 
-            let token = ctx.step(&gssapi_data)?;
+            let token = match ctx.step(&gssapi_data) {
+                Ok(token) => token,
+                Err(err) => {
+                    let retry_without_binding = is_fresh_negotiation
+                        && matches!(
+                            channel_binding.as_deref(),
+                            Some(ChannelBindingConfig {
+                                mode: ChannelBindingMode::Relaxed,
+                                ..
+                            })
+                        );
+                    if !retry_without_binding {
+                        return Err(err.into());
+                    }
+
+                    // `Relaxed` accepts clients that don't send a channel
+                    // binding at all: retry once against a fresh,
+                    // binding-less context so a client whose token simply
+                    // wasn't channel-bound still authenticates.
+                    debug!("channel binding check failed, retrying without it (relaxed mode)");
+                    ctx = backend.new_server_ctx(None)?;
+                    ctx.step(&gssapi_data)?
+                }
+            };
 
             if !ctx.is_complete() {
-                error!("currently only 2-pass SPNEGO is supported");
-                return Err(Error::MultipassSpnego);
+                debug!("negotiation incomplete, waiting for the next leg");
+                let Some(id) = continuations.insert(ctx) else {
+                    error!("continuation store is full, rejecting new negotiation");
+                    return Err(Error::NegotiationStoreFull);
+                };
+
+                let mut response = StatusCode::UNAUTHORIZED.into_response();
+                response.headers_mut().insert(
+                    WWW_AUTHENTICATE,
+                    format!(
+                        "Negotiate {}",
+                        token.map(|x| STANDARD.encode(&*x)).unwrap_or_default()
+                    )
+                    .parse()
+                    .expect("base64 to be ascii"),
+                );
+                response.headers_mut().insert(
+                    HeaderName::from_static(CONTINUATION_HEADER),
+                    id.encode().parse().expect("hex to be ascii"),
+                );
+                return Ok(response);
             };
 
-            let upn = ctx.source_name()?.to_string();
-            req.extensions_mut().insert(Upn(upn.into()));
+            let identity = backend.identity(&ctx)?;
+            req.extensions_mut().insert(identity.clone());
+            req.extensions_mut().insert(Groups(backend.groups(&ctx)?));
 
             let mut response = inner
                 .call(req)
@@ -224,18 +516,46 @@ where
                 .expect("base64 to be ascii"),
             );
 
+            if let Some(session) = &session {
+                set_session_cookie(&mut response, session, &identity);
+            }
+
             Ok(response)
         })
     }
 }
 
-fn new_server_ctx(principal: &str) -> Result<ServerCtx, Error> {
-    let name = Name::new(principal.as_bytes(), Some(&GSS_NT_KRB5_PRINCIPAL))?
-        .canonicalize(Some(&GSS_MECH_SPNEGO))?;
-    let cred = {
-        let mut s = OidSet::new()?;
-        s.add(&GSS_MECH_SPNEGO)?;
-        Cred::acquire(Some(&name), None, CredUsage::Accept, Some(&s))?
-    };
-    Ok(ServerCtx::new(cred))
+/// Builds the 401 challenge response: always offers `Negotiate`, and also
+/// offers `Basic realm="..."` when `basic_auth_realm` is set, i.e. the
+/// fallback is enabled. Negotiate is listed first so clients that can do
+/// both prefer it.
+fn unauthorized_challenge(basic_auth_realm: Option<&str>) -> Response {
+    let mut response = StatusCode::UNAUTHORIZED.into_response();
+    response
+        .headers_mut()
+        .append(WWW_AUTHENTICATE, HeaderValue::from_static("Negotiate"));
+
+    if let Some(realm) = basic_auth_realm {
+        let value = format!("Basic realm=\"{realm}\"");
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().append(WWW_AUTHENTICATE, value);
+        }
+    }
+
+    response
+}
+
+fn set_session_cookie<Be: NegotiateBackend>(
+    response: &mut Response,
+    session: &SessionConfig<Be>,
+    identity: &Be::Identity,
+) {
+    let ticket = session.signer.issue((session.to_subject)(identity), session.ttl);
+    let value = format!(
+        "{SESSION_COOKIE}={ticket}; Path=/; HttpOnly; Secure; SameSite=Strict; Max-Age={}",
+        session.ttl.as_secs()
+    );
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        response.headers_mut().append(SET_COOKIE, value);
+    }
 }
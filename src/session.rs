@@ -0,0 +1,75 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Name of the cookie used to carry a signed session ticket.
+pub(crate) const SESSION_COOKIE: &str = "axum-negotiate-session";
+
+/// Signs and verifies the session tickets issued after a successful GSSAPI
+/// negotiation, so later requests can skip `new_server_ctx`/`ctx.step()`
+/// entirely. A ticket is `base64url(upn "|" expiry) "." base64url(hmac)`;
+/// the HMAC covers the encoded payload bytes, not the decoded string.
+pub(crate) struct TicketSigner {
+    secret: Vec<u8>,
+}
+
+impl TicketSigner {
+    pub(crate) fn new(secret: Vec<u8>) -> Self {
+        Self { secret }
+    }
+
+    pub(crate) fn issue(&self, upn: &str, ttl: Duration) -> String {
+        let expiry = now() + ttl.as_secs();
+        let payload = format!("{upn}|{expiry}");
+        let payload = URL_SAFE_NO_PAD.encode(payload);
+        let sig = URL_SAFE_NO_PAD.encode(self.sign(payload.as_bytes()));
+        format!("{payload}.{sig}")
+    }
+
+    /// Returns the UPN the ticket was issued for, unless it is malformed,
+    /// tampered with, or expired.
+    pub(crate) fn verify(&self, ticket: &str) -> Option<String> {
+        let (payload, sig) = ticket.split_once('.')?;
+        let sig = URL_SAFE_NO_PAD.decode(sig).ok()?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("hmac accepts a key of any length");
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&sig).ok()?;
+
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let payload = String::from_utf8(payload).ok()?;
+        let (upn, expiry) = payload.rsplit_once('|')?;
+        let expiry: u64 = expiry.parse().ok()?;
+
+        if now() >= expiry {
+            return None;
+        }
+
+        Some(upn.to_owned())
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("hmac accepts a key of any length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Extracts the value of a single cookie from a `Cookie` header, if present.
+pub(crate) fn extract_cookie(header: &str, name: &str) -> Option<String> {
+    header.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        (key == name).then(|| value.to_owned())
+    })
+}